@@ -0,0 +1,57 @@
+use clap::Parser;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Runtime configuration, loaded from CLI flags with environment-variable
+/// fallbacks (see each field's `env` attribute).
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+pub(crate) struct Config {
+    /// Address the TCP listener binds to.
+    #[arg(long, env = "TCP_BIND_ADDR", default_value = "0.0.0.0:3000")]
+    pub(crate) tcp_bind_addr: String,
+
+    /// Address the optional WebSocket listener binds to. Unset disables it.
+    #[arg(long, env = "WS_BIND_ADDR")]
+    pub(crate) ws_bind_addr: Option<String>,
+
+    /// Per-message read timeout, in seconds.
+    #[arg(long, env = "TIMEOUT_SECONDS", default_value_t = 1)]
+    pub(crate) timeout_seconds: u64,
+
+    /// How long a robot is given to report `FULL POWER` after `RECHARGING`, in seconds.
+    #[arg(long, env = "RECHARGE_TIMEOUT_SECONDS", default_value_t = 5)]
+    pub(crate) recharge_timeout_seconds: u64,
+
+    /// Path to a JSON file with the server/client key-pair table. Falls back
+    /// to the built-in key table when unset.
+    #[arg(long, env = "KEY_TABLE_PATH")]
+    pub(crate) key_table_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyPair {
+    server: u16,
+    client: u16,
+}
+
+/// The key table baked into the binary, used when no `--key-table-path` is given.
+const DEFAULT_SERVER_KEYS: [u16; 5] = [23019, 32037, 18789, 16443, 18189];
+const DEFAULT_CLIENT_KEYS: [u16; 5] = [32037, 29295, 13603, 29533, 21952];
+
+/// Loads the `(server_key, client_key)` table from `path`, or the built-in
+/// defaults if `path` is `None`.
+pub(crate) fn load_key_table(path: Option<&Path>) -> Vec<(u16, u16)> {
+    let Some(path) = path else {
+        return DEFAULT_SERVER_KEYS
+            .into_iter()
+            .zip(DEFAULT_CLIENT_KEYS)
+            .collect();
+    };
+
+    let data = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read key table file {path:?}: {e}"));
+    let pairs: Vec<KeyPair> = serde_json::from_str(&data)
+        .unwrap_or_else(|e| panic!("invalid key table file {path:?}: {e}"));
+    pairs.into_iter().map(|p| (p.server, p.client)).collect()
+}
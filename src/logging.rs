@@ -5,8 +5,54 @@ pub(crate) fn set_up() {
     #[cfg(not(debug_assertions))]
     let level = tracing::Level::INFO;
 
-    let subscriber = tracing_subscriber::fmt().with_max_level(level).finish();
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    #[cfg(feature = "otel")]
+    set_up_with_otel(level);
+
+    #[cfg(not(feature = "otel"))]
+    {
+        let subscriber = tracing_subscriber::fmt().with_max_level(level).finish();
+        tracing::subscriber::set_global_default(subscriber).unwrap();
+    }
 
     tracing::debug!("logging set-up!")
 }
+
+/// When `OTEL_EXPORTER_OTLP_ENDPOINT` is set, exports spans and the counters
+/// in `crate::metrics` via OTLP; otherwise behaves like the plain fmt subscriber.
+#[cfg(feature = "otel")]
+fn set_up_with_otel(level: tracing::Level) {
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let registry = tracing_subscriber::registry()
+        .with(tracing_subscriber::filter::LevelFilter::from_level(level))
+        .with(tracing_subscriber::fmt::layer());
+
+    let Ok(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+        registry.init();
+        return;
+    };
+
+    crate::metrics::install(&endpoint);
+
+    let tracer = match opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&endpoint),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+    {
+        Ok(tracer) => tracer,
+        Err(e) => {
+            registry.init();
+            tracing::error!("failed to set up OTLP trace exporter: {e:?}");
+            return;
+        }
+    };
+
+    registry
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .init();
+}
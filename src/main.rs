@@ -1,29 +1,142 @@
+mod config;
 mod logging;
+mod metrics;
 mod robot;
+mod session;
+mod store;
+mod transport;
 mod util;
 
+use crate::config::Config;
 use crate::robot::RobotController;
+use crate::store::SecretStore;
+use crate::transport::{EncryptedTransport, PlaintextTransport, Transport, WsTransport};
+use clap::Parser;
 use std::io;
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
 use tracing::Instrument;
 
-async fn handle_client(stream: TcpStream) {
+/// When set, newly accepted connections perform the X25519 + ChaCha20-Poly1305
+/// handshake before the login flow instead of speaking plaintext.
+const ENCRYPTED_ENV_VAR: &str = "ROBOT_ENCRYPTED";
+
+#[derive(Clone)]
+struct Shared {
+    store: Arc<SecretStore>,
+    config: Arc<Config>,
+    key_table: Arc<Vec<(u16, u16)>>,
+}
+
+async fn handle_tcp_client(stream: TcpStream, shared: Shared) {
     let addr = stream.peer_addr().unwrap();
-    RobotController::start(stream)
-        .instrument(tracing::trace_span!("robot", addr = addr.to_string()))
+
+    let transport: Box<dyn Transport> = if std::env::var_os(ENCRYPTED_ENV_VAR).is_some() {
+        match EncryptedTransport::handshake(stream).await {
+            Ok(transport) => Box::new(transport),
+            Err(e) => {
+                tracing::error!("encrypted handshake failed: {e:?}");
+                return;
+            }
+        }
+    } else {
+        Box::new(PlaintextTransport::new(stream))
+    };
+
+    RobotController::start(transport, shared.store, shared.config, shared.key_table)
+        .instrument(tracing::trace_span!(
+            "robot",
+            addr = addr.to_string(),
+            key_id = tracing::field::Empty,
+            path_length = tracing::field::Empty
+        ))
         .await;
 }
 
+async fn handle_ws_client(stream: TcpStream, shared: Shared) {
+    let addr = stream.peer_addr().unwrap();
+
+    let ws = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            tracing::error!("websocket upgrade failed: {e:?}");
+            return;
+        }
+    };
+
+    RobotController::start(
+        Box::new(WsTransport::new(ws)),
+        shared.store,
+        shared.config,
+        shared.key_table,
+    )
+    .instrument(tracing::trace_span!(
+        "robot",
+        addr = addr.to_string(),
+        transport = "ws",
+        key_id = tracing::field::Empty,
+        path_length = tracing::field::Empty
+    ))
+    .await;
+}
+
+async fn run_tcp(addr: String, shared: Shared) -> io::Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    tracing::info!("listening on {addr} (tcp)");
+    loop {
+        let (socket, _) = listener.accept().await?;
+        tokio::spawn(handle_tcp_client(socket, shared.clone()));
+    }
+}
+
+async fn run_ws(addr: String, shared: Shared) -> io::Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    tracing::info!("listening on {addr} (websocket)");
+    loop {
+        let (socket, _) = listener.accept().await?;
+        tokio::spawn(handle_ws_client(socket, shared.clone()));
+    }
+}
+
 #[tokio::main]
 async fn main() -> io::Result<()> {
     logging::set_up();
 
-    let addr = "0.0.0.0:3000";
-    let listener = TcpListener::bind(addr).await?;
-    tracing::info!("listening on {addr}");
+    let config = Arc::new(Config::parse());
 
-    loop {
-        let (socket, _) = listener.accept().await?;
-        tokio::spawn(handle_client(socket));
+    let store = Arc::new(
+        SecretStore::connect("sqlite://secrets.db")
+            .await
+            .expect("failed to open secret store"),
+    );
+
+    match store.recent_pickups(5).await {
+        Ok(recent) => {
+            for secret in recent {
+                tracing::info!("previously recovered: {secret:?}");
+            }
+        }
+        Err(e) => tracing::error!("failed to read recent pickups: {e:?}"),
+    }
+
+    let key_table = Arc::new(config::load_key_table(config.key_table_path.as_deref()));
+
+    let shared = Shared {
+        store,
+        config: config.clone(),
+        key_table,
+    };
+
+    let tcp_addr = config.tcp_bind_addr.clone();
+
+    match config.ws_bind_addr.clone() {
+        Some(ws_addr) => {
+            let (tcp_result, ws_result) =
+                tokio::join!(run_tcp(tcp_addr, shared.clone()), run_ws(ws_addr, shared));
+            tcp_result?;
+            ws_result?;
+            Ok(())
+        }
+        None => run_tcp(tcp_addr, shared).await,
     }
 }
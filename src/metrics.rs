@@ -0,0 +1,95 @@
+//! Optional OpenTelemetry counters/histograms for fleet observability,
+//! gated behind the `otel` feature. Call sites elsewhere in the crate don't
+//! need to `#[cfg]` themselves — the calls are no-ops when the feature (or
+//! its exporter endpoint) isn't configured.
+
+#[cfg(feature = "otel")]
+mod enabled {
+    use once_cell::sync::Lazy;
+    use opentelemetry::metrics::{Counter, Histogram};
+    use opentelemetry::{global, KeyValue};
+
+    static LOGIN_ATTEMPTS: Lazy<Counter<u64>> = Lazy::new(|| {
+        global::meter("robot-controller")
+            .u64_counter("robot.logins.attempted")
+            .init()
+    });
+    static LOGIN_SUCCESSES: Lazy<Counter<u64>> = Lazy::new(|| {
+        global::meter("robot-controller")
+            .u64_counter("robot.logins.succeeded")
+            .init()
+    });
+    static LOGIN_FAILURES: Lazy<Counter<u64>> = Lazy::new(|| {
+        global::meter("robot-controller")
+            .u64_counter("robot.logins.failed")
+            .init()
+    });
+    static RECHARGE_EVENTS: Lazy<Counter<u64>> = Lazy::new(|| {
+        global::meter("robot-controller")
+            .u64_counter("robot.recharges")
+            .init()
+    });
+    static OBSTACLES_RAMMED: Lazy<Counter<u64>> = Lazy::new(|| {
+        global::meter("robot-controller")
+            .u64_counter("robot.obstacles_rammed")
+            .init()
+    });
+    static TIME_TO_ORIGIN: Lazy<Histogram<f64>> = Lazy::new(|| {
+        global::meter("robot-controller")
+            .f64_histogram("robot.time_to_origin_seconds")
+            .init()
+    });
+
+    pub(crate) fn login_attempted() {
+        LOGIN_ATTEMPTS.add(1, &[]);
+    }
+
+    pub(crate) fn login_succeeded() {
+        LOGIN_SUCCESSES.add(1, &[]);
+    }
+
+    pub(crate) fn login_failed(cause: &'static str) {
+        LOGIN_FAILURES.add(1, &[KeyValue::new("cause", cause)]);
+    }
+
+    pub(crate) fn recharge_event() {
+        RECHARGE_EVENTS.add(1, &[]);
+    }
+
+    pub(crate) fn obstacle_rammed() {
+        OBSTACLES_RAMMED.add(1, &[]);
+    }
+
+    pub(crate) fn time_to_origin(seconds: f64) {
+        TIME_TO_ORIGIN.record(seconds, &[]);
+    }
+
+    /// Builds and installs the global OTLP meter provider. Logs and leaves
+    /// metrics disabled if `endpoint` can't be reached at start-up.
+    pub(crate) fn install(endpoint: &str) {
+        let exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(endpoint);
+
+        match opentelemetry_otlp::new_pipeline()
+            .metrics(opentelemetry_sdk::runtime::Tokio)
+            .with_exporter(exporter)
+            .build()
+        {
+            Ok(provider) => global::set_meter_provider(provider),
+            Err(e) => tracing::error!("failed to set up OTLP metrics exporter: {e:?}"),
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod enabled {
+    pub(crate) fn login_attempted() {}
+    pub(crate) fn login_succeeded() {}
+    pub(crate) fn login_failed(_cause: &'static str) {}
+    pub(crate) fn recharge_event() {}
+    pub(crate) fn obstacle_rammed() {}
+    pub(crate) fn time_to_origin(_seconds: f64) {}
+}
+
+pub(crate) use enabled::*;
@@ -1,18 +1,27 @@
-use crate::util::{ClientMessage, MessageReceivedError, ServerMessage, CLIENT_KEYS, SERVER_KEYS};
+use crate::config::Config;
+use crate::session;
+use crate::store::SecretStore;
+use crate::transport::Transport;
+use crate::util::{ClientMessage, MessageReceivedError, ServerMessage};
 use async_recursion::async_recursion;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
 
-#[derive(Debug)]
+/// Cap for the very first frame of a connection, which is either a login
+/// name or a resume attempt. A resume message is `"RESUME " + <uuid v4>`,
+/// i.e. 7 + 36 = 43 bytes. The length checks in `Transport::recv_frame`
+/// reject a frame whose content is `>= max_length`, so the cap must be at
+/// least one more than 43 or a full-length token is itself rejected as
+/// `TooLong` before the `RESUME ` prefix check ever runs.
+const FIRST_MESSAGE_MAX_LENGTH: usize = 44;
+
+#[derive(Debug, Clone, Copy)]
 enum Position {
     Unknown,
     Known(i32, i32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Direction {
     Unknown,
     Up,
@@ -27,85 +36,98 @@ enum MoveResult {
     Rammed,
 }
 
-#[derive(Debug)]
-struct Robot {
+#[derive(Debug, Clone)]
+pub(crate) struct Robot {
+    name: Option<String>,
+    key_id: Option<usize>,
     position: Position,
     direction: Direction,
+    /// Count of successful forward moves, for the audit trail written on pickup.
+    path_length: u32,
+}
+
+/// Where a run loop is within a session, so a resumed connection can pick up
+/// from the right point instead of redoing steps a fresh login already did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    LoggedIn,
+    Navigating,
+    PickingUp,
 }
 
 pub(crate) struct RobotController {
-    socket: TcpStream,
+    transport: Box<dyn Transport>,
     robot: Robot,
+    phase: Phase,
+    /// Set once `log_in` (or a successful resume) hands out a session token,
+    /// so later phase transitions know where to persist themselves.
+    session_token: Option<session::Token>,
+    store: Arc<SecretStore>,
+    config: Arc<Config>,
+    key_table: Arc<Vec<(u16, u16)>>,
 }
 
 impl RobotController {
-    pub(crate) async fn start(socket: TcpStream) {
+    pub(crate) async fn start(
+        transport: Box<dyn Transport>,
+        store: Arc<SecretStore>,
+        config: Arc<Config>,
+        key_table: Arc<Vec<(u16, u16)>>,
+    ) {
         tracing::info!("connected!");
         Self {
-            socket,
+            transport,
             robot: Robot {
+                name: None,
+                key_id: None,
                 position: Position::Unknown,
                 direction: Direction::Unknown,
+                path_length: 0,
             },
+            phase: Phase::LoggedIn,
+            session_token: None,
+            store,
+            config,
+            key_table,
         }
         .run()
         .await;
         tracing::info!("disconnected!");
     }
 
+    /// Persists the current robot state under the active session token, if any.
+    fn save_session(&self) {
+        if let Some(token) = &self.session_token {
+            session::save(token, self.robot.clone(), self.phase);
+        }
+    }
+
     async fn send(&mut self, msg: &ServerMessage) -> Option<()> {
-        if let Err(e) = self.socket.write_all(msg.to_string().as_bytes()).await {
+        if let Err(e) = self.transport.send_frame(msg.to_string().as_bytes()).await {
             tracing::error!("connection interrupted! ({e:?})");
             return None;
         };
         Some(())
     }
 
-    async fn receive<const MAX_LENGTH: usize, const TIMEOUT_SECONDS: u64>(
+    async fn receive(
         &mut self,
+        max_length: usize,
+        timeout_seconds: u64,
     ) -> Result<ClientMessage, MessageReceivedError> {
-        const SEP: &str = "\x07\x08";
-        const SEP_LEN: usize = SEP.len();
-
-        let mut data = [0u8; 256];
-        let mut i = 0usize;
-
-        loop {
-            match tokio::time::timeout(
-                Duration::from_secs(TIMEOUT_SECONDS),
-                self.socket.read(&mut data[i..i + 1]),
-            )
-            .await
-            {
-                Ok(res) => {
-                    if let Err(e) = res {
-                        tracing::error!("err: {e:?}");
-                        return Err(MessageReceivedError::IOError(e));
-                    }
-                }
-                Err(_e) => {
-                    tracing::error!("timeout exceeded!");
-                    return Err(MessageReceivedError::TimedOut);
-                }
-            }
-
-            if i >= SEP_LEN && core::str::from_utf8(&data[i - (SEP_LEN - 1)..=i]).unwrap() == SEP {
-                break;
-            }
-
-            i += 1;
-
-            if i == MAX_LENGTH {
-                return Err(MessageReceivedError::TooLong);
-            }
-        }
-
-        ClientMessage::parse(core::str::from_utf8(&data[0..i - 1]).unwrap())
+        let message = self.transport.recv_frame(max_length, timeout_seconds).await?;
+        ClientMessage::parse(core::str::from_utf8(&message).unwrap())
             .ok_or(MessageReceivedError::Invalid)
     }
 
     async fn wait_for_recharging(&mut self) -> Option<()> {
-        match tokio::time::timeout(Duration::from_secs(5), self.receive::<12, 5>()).await {
+        let recharge_timeout_seconds = self.config.recharge_timeout_seconds;
+        match tokio::time::timeout(
+            Duration::from_secs(recharge_timeout_seconds),
+            self.receive(12, recharge_timeout_seconds),
+        )
+        .await
+        {
             Ok(Ok(msg)) => match msg {
                 ClientMessage::FullPower => Some(()),
                 _ => {
@@ -122,11 +144,12 @@ impl RobotController {
                 None
             }
         }
+        .map(|()| crate::metrics::recharge_event())
     }
 
     #[async_recursion]
-    async fn get<const MAX_LENGTH: usize>(&mut self) -> Option<ClientMessage> {
-        match self.receive::<MAX_LENGTH, 1>().await {
+    async fn get(&mut self, max_length: usize) -> Option<ClientMessage> {
+        match self.receive(max_length, self.config.timeout_seconds).await {
             Ok(msg) => match msg {
                 ClientMessage::Recharging => {
                     tracing::trace!("recharging!");
@@ -134,7 +157,7 @@ impl RobotController {
                         return None;
                     }
                     tracing::trace!("full power!");
-                    self.get::<MAX_LENGTH>().await
+                    self.get(max_length).await
                 }
                 ClientMessage::FullPower => {
                     self.send(&ServerMessage::LogicError).await?;
@@ -153,33 +176,40 @@ impl RobotController {
         }
     }
 
-    async fn log_in(&mut self) -> Option<()> {
-        let msg = self.get::<20>().await?;
-        let ClientMessage::String(name) = msg else {
-            tracing::error!("wrong variant received: {msg:?}");
+    async fn log_in(&mut self, name_msg: ClientMessage) -> Option<()> {
+        crate::metrics::login_attempted();
+
+        let ClientMessage::String(name) = name_msg else {
+            tracing::error!("wrong variant received: {name_msg:?}");
             self.send(&ServerMessage::SyntaxError).await?;
+            crate::metrics::login_failed("SyntaxError");
             return None;
         };
         tracing::debug!("name: {name:?}");
+        self.robot.name = Some(name.clone());
 
         self.send(&ServerMessage::KeyRequest).await;
 
-        let msg = self.get::<12>().await?;
+        let msg = self.get(12).await?;
         let ClientMessage::Number(key_id) = msg else {
             tracing::error!("wrong variant received: {msg:?}");
             self.send(&ServerMessage::SyntaxError).await?;
+            crate::metrics::login_failed("SyntaxError");
             return None;
         };
         tracing::debug!("key_id: {key_id}");
 
-        if key_id > 4 {
+        if key_id >= self.key_table.len() {
             tracing::info!("key_id: {key_id} is out of range, disconnecting...");
             self.send(&ServerMessage::KeyOutOfRangeError).await?;
+            crate::metrics::login_failed("KeyOutOfRangeError");
             return None;
         }
 
-        let server_key = SERVER_KEYS[key_id];
-        let client_key = CLIENT_KEYS[key_id];
+        self.robot.key_id = Some(key_id);
+        tracing::Span::current().record("key_id", key_id);
+
+        let (server_key, client_key) = self.key_table[key_id];
 
         let name_char_sum: u16 = name.into_bytes().into_iter().map(|x| x as u16).sum();
         let checksum = name_char_sum.wrapping_mul(1000);
@@ -187,29 +217,33 @@ impl RobotController {
         self.send(&ServerMessage::Confirmation(server_checksum))
             .await?;
 
-        let msg = self.get::<12>().await?;
+        let msg = self.get(12).await?;
         let ClientMessage::Number(client_checksum) = msg else {
             tracing::error!("wrong variant received: {msg:?}");
             self.send(&ServerMessage::SyntaxError).await?;
+            crate::metrics::login_failed("SyntaxError");
             return None;
         };
         let Ok(client_checksum): Result<u16,_> = client_checksum.try_into() else {
             tracing::error!("invalid client checksum!");
             self.send(&ServerMessage::SyntaxError).await?;
+            crate::metrics::login_failed("SyntaxError");
             return None;
         };
         if checksum != client_checksum.wrapping_sub(client_key) {
             self.send(&ServerMessage::LoginFailed).await?;
+            crate::metrics::login_failed("LoginFailed");
             return None;
         }
 
         self.send(&ServerMessage::OK).await?;
+        crate::metrics::login_succeeded();
         Some(())
     }
 
     async fn pick_up_secret(&mut self) -> Option<()> {
         self.send(&ServerMessage::PickUp).await;
-        let secret = match self.get::<100>().await? {
+        let secret = match self.get(100).await? {
             ClientMessage::String(secret) => secret,
             ClientMessage::Number(secret) => secret.to_string(),
             msg => {
@@ -219,6 +253,18 @@ impl RobotController {
             }
         };
         tracing::debug!("secret found: {secret:?}");
+        tracing::Span::current().record("path_length", self.robot.path_length);
+
+        if let (Some(name), Some(key_id)) = (self.robot.name.clone(), self.robot.key_id) {
+            if let Err(e) = self
+                .store
+                .record_pickup(&name, key_id, &secret, self.robot.path_length)
+                .await
+            {
+                tracing::error!("failed to persist recovered secret: {e:?}");
+            }
+        }
+
         Some(())
     }
 
@@ -230,7 +276,7 @@ impl RobotController {
 
     async fn move_forward(&mut self) -> Option<MoveResult> {
         self.send(&ServerMessage::Move).await;
-        let msg = self.get::<12>().await?;
+        let msg = self.get(12).await?;
         let ClientMessage::Ok(new_x, new_y) = msg else {
             tracing::error!("wrong variant received: {msg:?}");
             self.send(&ServerMessage::SyntaxError).await;
@@ -238,6 +284,7 @@ impl RobotController {
         };
         if let Position::Known(x, y) = self.robot.position {
             if (new_x - x, new_y - y) == (0, 0) {
+                crate::metrics::obstacle_rammed();
                 return Some(MoveResult::Rammed);
             }
             if let Direction::Unknown = self.robot.direction {
@@ -252,12 +299,13 @@ impl RobotController {
             }
         }
         self.robot.position = Position::Known(new_x, new_y);
+        self.robot.path_length += 1;
         Some(MoveResult::Ok)
     }
 
     async fn turn(&mut self, turn_message: ServerMessage) -> Option<()> {
         self.send(&turn_message).await;
-        let msg = self.get::<12>().await?;
+        let msg = self.get(12).await?;
         let ClientMessage::Ok(x, y) = msg else {
             tracing::error!("wrong variant received: {msg:?}");
             self.send(&ServerMessage::SyntaxError).await;
@@ -327,11 +375,68 @@ impl RobotController {
     }
 
     pub(crate) async fn run(&mut self) -> Option<()> {
-        self.log_in().await?;
+        let msg = self.get(FIRST_MESSAGE_MAX_LENGTH).await?;
+        if let ClientMessage::String(s) = &msg {
+            if let Some(token) = s.strip_prefix("RESUME ") {
+                return self.resume(token.to_owned()).await;
+            }
+        }
+
+        self.log_in(msg).await?;
+        self.phase = Phase::LoggedIn;
+        let token = session::create(self.robot.clone(), self.phase);
+        self.send(&ServerMessage::SessionToken(token.clone()))
+            .await?;
+        self.session_token = Some(token);
 
         self.acquire_initial_state().await?;
+        self.phase = Phase::Navigating;
+        self.save_session();
         tracing::trace!("initial state retrieved!");
 
+        self.navigate().await?;
+
+        self.phase = Phase::PickingUp;
+        self.save_session();
+
+        self.pick_up_secret().await?;
+        self.log_out().await;
+        Some(())
+    }
+
+    /// Restores a previously saved `Robot`/`Phase` and continues the run loop
+    /// from that point instead of redoing login/state-acquisition.
+    async fn resume(&mut self, token: session::Token) -> Option<()> {
+        let Some((robot, phase)) = session::resume(&token) else {
+            tracing::info!("unknown or expired resume token");
+            self.send(&ServerMessage::SyntaxError).await?;
+            return None;
+        };
+        tracing::info!("resuming session for {:?}, phase: {phase:?}", robot.name);
+        self.robot = robot;
+        self.phase = phase;
+        self.session_token = Some(token);
+
+        if self.phase == Phase::LoggedIn {
+            self.acquire_initial_state().await?;
+            self.phase = Phase::Navigating;
+            self.save_session();
+        }
+
+        if self.phase == Phase::Navigating {
+            self.navigate().await?;
+            self.phase = Phase::PickingUp;
+            self.save_session();
+        }
+
+        self.pick_up_secret().await?;
+        self.log_out().await;
+        Some(())
+    }
+
+    async fn navigate(&mut self) -> Option<()> {
+        let started_at = std::time::Instant::now();
+
         loop {
             tracing::trace!("{:?}", self.robot);
             let Position::Known(x, y) = self.robot.position else { unreachable!() };
@@ -375,10 +480,127 @@ impl RobotController {
                 self.rotate(&direction).await?;
                 self.move_forward().await?;
             }
+            // Save after every step, not just at phase boundaries: `navigate`
+            // can run for many moves, and a connection that drops mid-loop
+            // should resume near where it left off, not from whatever
+            // position was current when `navigate` started.
+            self.save_session();
         }
 
-        self.pick_up_secret().await?;
-        self.log_out().await;
+        crate::metrics::time_to_origin(started_at.elapsed().as_secs_f64());
         Some(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{PlaintextTransport, WsTransport};
+    use clap::Parser;
+    use futures_util::SinkExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::Message;
+
+    async fn controller_for_test(transport: Box<dyn Transport>) -> RobotController {
+        RobotController {
+            transport,
+            robot: Robot {
+                name: None,
+                key_id: None,
+                position: Position::Unknown,
+                direction: Direction::Unknown,
+                path_length: 0,
+            },
+            phase: Phase::LoggedIn,
+            session_token: None,
+            store: Arc::new(SecretStore::connect("sqlite::memory:").await.unwrap()),
+            config: Arc::new(Config::parse_from(["test"])),
+            key_table: Arc::new(vec![(0, 0)]),
+        }
+    }
+
+    /// A resume token must survive the `FIRST_MESSAGE_MAX_LENGTH` cap on a
+    /// fresh connection, or every issued token is unusable (see the
+    /// `FIRST_MESSAGE_MAX_LENGTH` doc comment).
+    #[tokio::test]
+    async fn resume_token_round_trips_through_a_fresh_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let token = session::create(
+            Robot {
+                name: Some("bender".to_owned()),
+                key_id: Some(0),
+                position: Position::Known(0, 0),
+                direction: Direction::Unknown,
+                path_length: 3,
+            },
+            Phase::PickingUp,
+        );
+        let expected = format!("RESUME {token}");
+
+        let client = tokio::spawn({
+            let expected = expected.clone();
+            async move {
+                let mut socket = TcpStream::connect(addr).await.unwrap();
+                socket
+                    .write_all(format!("{expected}\x07\x08").as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut controller = controller_for_test(Box::new(PlaintextTransport::new(socket))).await;
+        let msg = controller.get(FIRST_MESSAGE_MAX_LENGTH).await;
+        client.await.unwrap();
+
+        let Some(ClientMessage::String(received)) = msg else {
+            panic!("expected the resume token to be read in full, got {msg:?}");
+        };
+        assert_eq!(received, expected);
+    }
+
+    /// The WebSocket transport's length check must accept the same
+    /// full-length resume token as the plaintext transport — see
+    /// `resume_token_round_trips_through_a_fresh_connection`.
+    #[tokio::test]
+    async fn resume_token_round_trips_over_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let token = session::create(
+            Robot {
+                name: Some("bender".to_owned()),
+                key_id: Some(0),
+                position: Position::Known(0, 0),
+                direction: Direction::Unknown,
+                path_length: 3,
+            },
+            Phase::PickingUp,
+        );
+        let expected = format!("RESUME {token}");
+
+        let client = tokio::spawn({
+            let expected = expected.clone();
+            async move {
+                let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+                    .await
+                    .unwrap();
+                ws.send(Message::Text(expected)).await.unwrap();
+            }
+        });
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+        let mut controller = controller_for_test(Box::new(WsTransport::new(ws))).await;
+        let msg = controller.get(FIRST_MESSAGE_MAX_LENGTH).await;
+        client.await.unwrap();
+
+        let Some(ClientMessage::String(received)) = msg else {
+            panic!("expected the resume token to be read in full over websocket, got {msg:?}");
+        };
+        assert_eq!(received, expected);
+    }
+}
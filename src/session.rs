@@ -0,0 +1,59 @@
+use crate::robot::{Phase, Robot};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long an abandoned session is kept around before it's evicted.
+const SESSION_TTL: Duration = Duration::from_secs(300);
+
+pub(crate) type Token = String;
+
+struct SessionState {
+    robot: Robot,
+    phase: Phase,
+    expires_at: Instant,
+}
+
+static SESSIONS: Lazy<DashMap<Token, SessionState>> = Lazy::new(DashMap::new);
+
+fn evict_expired() {
+    let now = Instant::now();
+    SESSIONS.retain(|_, state| state.expires_at > now);
+}
+
+/// Persists `robot`/`phase` under a freshly issued token and returns it.
+pub(crate) fn create(robot: Robot, phase: Phase) -> Token {
+    evict_expired();
+    let token = Uuid::new_v4().to_string();
+    SESSIONS.insert(
+        token.clone(),
+        SessionState {
+            robot,
+            phase,
+            expires_at: Instant::now() + SESSION_TTL,
+        },
+    );
+    token
+}
+
+/// Overwrites the saved state for `token`, refreshing its TTL. A no-op if the
+/// session was already evicted, since there's nothing useful left to save.
+pub(crate) fn save(token: &Token, robot: Robot, phase: Phase) {
+    if let Some(mut state) = SESSIONS.get_mut(token) {
+        state.robot = robot;
+        state.phase = phase;
+        state.expires_at = Instant::now() + SESSION_TTL;
+    }
+}
+
+/// Returns the saved state for `token`, if any and not expired, refreshing
+/// its TTL. The entry is kept (not removed) so that a resumed connection
+/// which drops again before its next `save` still has *something* to
+/// resume from, instead of losing the session outright.
+pub(crate) fn resume(token: &str) -> Option<(Robot, Phase)> {
+    evict_expired();
+    let mut state = SESSIONS.get_mut(token)?;
+    state.expires_at = Instant::now() + SESSION_TTL;
+    Some((state.robot.clone(), state.phase))
+}
@@ -0,0 +1,75 @@
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::SqlitePool;
+use std::str::FromStr;
+
+/// Durable record of every secret a robot has successfully recovered, so a
+/// completed run leaves an auditable trail instead of just a log line.
+pub(crate) struct SecretStore {
+    pool: SqlitePool,
+}
+
+impl SecretStore {
+    pub(crate) async fn connect(database_url: &str) -> sqlx::Result<Self> {
+        let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS recovered_secrets (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                robot_name TEXT NOT NULL,
+                key_id INTEGER NOT NULL,
+                secret TEXT NOT NULL,
+                path_length INTEGER NOT NULL,
+                recovered_at TEXT NOT NULL DEFAULT (datetime('now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Records one successful pickup. `key_id` and `path_length` are stored
+    /// as-is for later auditing; this never overwrites a previous row.
+    pub(crate) async fn record_pickup(
+        &self,
+        robot_name: &str,
+        key_id: usize,
+        secret: &str,
+        path_length: u32,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO recovered_secrets (robot_name, key_id, secret, path_length)
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(robot_name)
+        .bind(key_id as i64)
+        .bind(secret)
+        .bind(path_length)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the most recently recovered secrets, newest first — the
+    /// minimal audit query path for operators.
+    pub(crate) async fn recent_pickups(&self, limit: i64) -> sqlx::Result<Vec<RecoveredSecret>> {
+        sqlx::query_as(
+            "SELECT robot_name, key_id, secret, path_length, recovered_at
+             FROM recovered_secrets ORDER BY id DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub(crate) struct RecoveredSecret {
+    pub(crate) robot_name: String,
+    pub(crate) key_id: i64,
+    pub(crate) secret: String,
+    pub(crate) path_length: i64,
+    pub(crate) recovered_at: String,
+}
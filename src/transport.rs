@@ -0,0 +1,352 @@
+use crate::util::MessageReceivedError;
+use async_trait::async_trait;
+use bytes::BytesMut;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use futures_util::{SinkExt, StreamExt};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// Number of bytes pulled from the socket per `read` call while framing a message.
+const READ_CHUNK_SIZE: usize = 256;
+
+const SEP: &[u8] = b"\x07\x08";
+
+/// Carries framed protocol messages over the wire, hiding whether the
+/// underlying connection is plaintext or sealed.
+#[async_trait]
+pub(crate) trait Transport: Send {
+    /// Sends one complete message, without the `\x07\x08` separator.
+    async fn send_frame(&mut self, payload: &[u8]) -> std::io::Result<()>;
+
+    /// Receives one complete message, without the `\x07\x08` separator.
+    async fn recv_frame(
+        &mut self,
+        max_length: usize,
+        timeout_seconds: u64,
+    ) -> Result<Vec<u8>, MessageReceivedError>;
+}
+
+/// Plain-text transport: the current wire format, messages separated by
+/// `\x07\x08` in a single byte stream.
+pub(crate) struct PlaintextTransport {
+    socket: TcpStream,
+    /// Bytes read from the socket but not yet consumed into a complete message.
+    /// Chunked reads can pull in bytes past the separator (e.g. the start of
+    /// the next pipelined message), so anything after it must be kept here
+    /// instead of discarded.
+    buf: BytesMut,
+}
+
+impl PlaintextTransport {
+    pub(crate) fn new(socket: TcpStream) -> Self {
+        Self {
+            socket,
+            buf: BytesMut::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for PlaintextTransport {
+    async fn send_frame(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        self.socket.write_all(payload).await?;
+        self.socket.write_all(SEP).await
+    }
+
+    async fn recv_frame(
+        &mut self,
+        max_length: usize,
+        timeout_seconds: u64,
+    ) -> Result<Vec<u8>, MessageReceivedError> {
+        loop {
+            if let Some(pos) = self.buf.windows(SEP.len()).position(|window| window == SEP) {
+                if pos >= max_length {
+                    return Err(MessageReceivedError::TooLong);
+                }
+                let message = self.buf.split_to(pos);
+                let _ = self.buf.split_to(SEP.len());
+                return Ok(message.to_vec());
+            }
+
+            if self.buf.len() >= max_length {
+                return Err(MessageReceivedError::TooLong);
+            }
+
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            let n = match tokio::time::timeout(
+                Duration::from_secs(timeout_seconds),
+                self.socket.read(&mut chunk),
+            )
+            .await
+            {
+                Ok(Ok(n)) => n,
+                Ok(Err(e)) => {
+                    tracing::error!("err: {e:?}");
+                    return Err(MessageReceivedError::IOError(e));
+                }
+                Err(_e) => {
+                    tracing::error!("timeout exceeded!");
+                    return Err(MessageReceivedError::TimedOut);
+                }
+            };
+
+            if n == 0 {
+                return Err(MessageReceivedError::IOError(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed",
+                )));
+            }
+
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// Size in bytes of a ChaCha20-Poly1305 nonce.
+const NONCE_LEN: usize = 12;
+/// Size in bytes of the Poly1305 authentication tag appended to every ciphertext.
+const TAG_LEN: usize = 16;
+
+/// ChaCha20-Poly1305 sealed transport. Framing switches from separator
+/// scanning to length-prefixing, since the payload is no longer textual:
+/// each frame on the wire is `u32 length || nonce || ciphertext || tag`.
+///
+/// The server and client sides of a connection each get their own key,
+/// derived from the shared secret with a direction label, so the two
+/// directions never encrypt under the same (key, nonce) pair even though
+/// both sides start their per-direction counter at zero.
+pub(crate) struct EncryptedTransport {
+    socket: TcpStream,
+    send_cipher: ChaCha20Poly1305,
+    recv_cipher: ChaCha20Poly1305,
+    send_counter: u64,
+    recv_counter: u64,
+}
+
+impl EncryptedTransport {
+    /// Performs the post-connect X25519 key exchange and wraps `socket` in
+    /// an encrypted transport. Both peers send their public key as the
+    /// first message, in either order. This side always plays the server
+    /// role, so "server->client"/"client->server" unambiguously assign the
+    /// send/recv keys.
+    pub(crate) async fn handshake(mut socket: TcpStream) -> std::io::Result<Self> {
+        let secret = EphemeralSecret::random();
+        let public = PublicKey::from(&secret);
+
+        socket.write_all(public.as_bytes()).await?;
+
+        let mut peer_public_bytes = [0u8; 32];
+        socket.read_exact(&mut peer_public_bytes).await?;
+        let peer_public = PublicKey::from(peer_public_bytes);
+
+        let shared_secret = secret.diffie_hellman(&peer_public);
+        let send_cipher =
+            ChaCha20Poly1305::new(&Self::derive_key(&shared_secret, b"server->client"));
+        let recv_cipher =
+            ChaCha20Poly1305::new(&Self::derive_key(&shared_secret, b"client->server"));
+
+        Ok(Self {
+            socket,
+            send_cipher,
+            recv_cipher,
+            send_counter: 0,
+            recv_counter: 0,
+        })
+    }
+
+    /// Derives a direction-specific key from the DH shared secret, so the
+    /// two directions of a connection never share a key (and therefore
+    /// never share a (key, nonce) pair, even with counters that both start
+    /// at zero).
+    fn derive_key(shared_secret: &x25519_dalek::SharedSecret, label: &[u8]) -> Key {
+        let mut hasher = Sha256::new();
+        hasher.update(shared_secret.as_bytes());
+        hasher.update(label);
+        Key::clone_from_slice(&hasher.finalize())
+    }
+
+    fn nonce_for(counter: u64) -> Nonce {
+        let mut bytes = [0u8; NONCE_LEN];
+        bytes[4..].copy_from_slice(&counter.to_be_bytes());
+        Nonce::clone_from_slice(&bytes)
+    }
+}
+
+#[async_trait]
+impl Transport for EncryptedTransport {
+    async fn send_frame(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        let nonce = Self::nonce_for(self.send_counter);
+        let ciphertext = self.send_cipher.encrypt(&nonce, payload).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::Other, "failed to seal frame")
+        })?;
+
+        let mut sealed = Vec::with_capacity(nonce.len() + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        self.socket
+            .write_all(&(sealed.len() as u32).to_be_bytes())
+            .await?;
+        self.socket.write_all(&sealed).await?;
+
+        self.send_counter += 1;
+        Ok(())
+    }
+
+    async fn recv_frame(
+        &mut self,
+        max_length: usize,
+        timeout_seconds: u64,
+    ) -> Result<Vec<u8>, MessageReceivedError> {
+        let timeout = Duration::from_secs(timeout_seconds);
+
+        let mut len_bytes = [0u8; 4];
+        match tokio::time::timeout(timeout, self.socket.read_exact(&mut len_bytes)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                tracing::error!("err: {e:?}");
+                return Err(MessageReceivedError::IOError(e));
+            }
+            Err(_e) => {
+                tracing::error!("timeout exceeded!");
+                return Err(MessageReceivedError::TimedOut);
+            }
+        }
+        let len = u32::from_be_bytes(len_bytes) as usize;
+
+        // Reject an oversized frame before allocating: `len` comes straight
+        // off the wire, so an unchecked allocation here is an attacker
+        // controlled up-to-4GiB allocation per frame.
+        if len >= max_length + NONCE_LEN + TAG_LEN {
+            tracing::error!("sealed frame of {len} bytes exceeds max_length, closing connection!");
+            return Err(MessageReceivedError::TooLong);
+        }
+
+        let mut sealed = vec![0u8; len];
+        match tokio::time::timeout(timeout, self.socket.read_exact(&mut sealed)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                tracing::error!("err: {e:?}");
+                return Err(MessageReceivedError::IOError(e));
+            }
+            Err(_e) => {
+                tracing::error!("timeout exceeded!");
+                return Err(MessageReceivedError::TimedOut);
+            }
+        }
+
+        if sealed.len() < NONCE_LEN {
+            tracing::error!("sealed frame shorter than a nonce, closing connection!");
+            return Err(MessageReceivedError::IOError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "sealed frame too short",
+            )));
+        }
+
+        let (nonce_bytes, ciphertext) = sealed.split_at(NONCE_LEN);
+        let expected_nonce = Self::nonce_for(self.recv_counter);
+        if nonce_bytes != expected_nonce.as_slice() {
+            tracing::error!("nonce counter mismatch, closing connection!");
+            return Err(MessageReceivedError::IOError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "nonce counter reuse or desync",
+            )));
+        }
+
+        let plaintext = self
+            .recv_cipher
+            .decrypt(&expected_nonce, ciphertext)
+            .map_err(|_| {
+                tracing::error!("tag verification failed, closing connection!");
+                MessageReceivedError::IOError(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "tag verification failed",
+                ))
+            })?;
+
+        self.recv_counter += 1;
+        Ok(plaintext)
+    }
+}
+
+/// WebSocket transport for browser-based or proxy-fronted clients. Each
+/// `\x07\x08`-terminated protocol frame maps to one text WebSocket message,
+/// stripping/appending the separator at the boundary instead of scanning a
+/// byte stream for it.
+pub(crate) struct WsTransport {
+    ws: WebSocketStream<TcpStream>,
+}
+
+impl WsTransport {
+    pub(crate) fn new(ws: WebSocketStream<TcpStream>) -> Self {
+        Self { ws }
+    }
+}
+
+#[async_trait]
+impl Transport for WsTransport {
+    async fn send_frame(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        let text = String::from_utf8_lossy(payload).into_owned();
+        self.ws
+            .send(Message::Text(text))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+    }
+
+    async fn recv_frame(
+        &mut self,
+        max_length: usize,
+        timeout_seconds: u64,
+    ) -> Result<Vec<u8>, MessageReceivedError> {
+        let next_message = async {
+            loop {
+                match self.ws.next().await {
+                    Some(Ok(Message::Text(text))) => return Ok(text.into_bytes()),
+                    Some(Ok(Message::Binary(bytes))) => return Ok(bytes),
+                    Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue,
+                    Some(Ok(Message::Close(_))) | None => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "websocket closed",
+                        ))
+                    }
+                    Some(Err(e)) => {
+                        return Err(std::io::Error::new(std::io::ErrorKind::Other, e))
+                    }
+                }
+            }
+        };
+
+        let message = match tokio::time::timeout(
+            Duration::from_secs(timeout_seconds),
+            next_message,
+        )
+        .await
+        {
+            Ok(Ok(message)) => message,
+            Ok(Err(e)) => {
+                tracing::error!("err: {e:?}");
+                return Err(MessageReceivedError::IOError(e));
+            }
+            Err(_e) => {
+                tracing::error!("timeout exceeded!");
+                return Err(MessageReceivedError::TimedOut);
+            }
+        };
+
+        if message.len() >= max_length {
+            return Err(MessageReceivedError::TooLong);
+        }
+
+        Ok(message)
+    }
+}
@@ -1,8 +1,5 @@
 use thiserror::Error;
 
-pub(crate) const SERVER_KEYS: [u16; 5] = [23019, 32037, 18789, 16443, 18189];
-pub(crate) const CLIENT_KEYS: [u16; 5] = [32037, 29295, 13603, 29533, 21952];
-
 #[derive(Debug)]
 pub(crate) enum ServerMessage {
     Confirmation(u16),
@@ -12,6 +9,8 @@ pub(crate) enum ServerMessage {
     PickUp,
     Logout,
     KeyRequest,
+    /// Server-issued resume token, sent once right after a successful login.
+    SessionToken(String),
     OK,
     LoginFailed,
     SyntaxError,
@@ -20,24 +19,24 @@ pub(crate) enum ServerMessage {
 }
 
 impl ToString for ServerMessage {
+    /// The message payload, *without* the `\x07\x08` separator — framing is
+    /// the transport's responsibility (see `transport::Transport`).
     fn to_string(&self) -> String {
-        format!(
-            "{}\x07\x08",
-            match self {
-                ServerMessage::Confirmation(x) => x.to_string(),
-                ServerMessage::Move => "102 MOVE".to_owned(),
-                ServerMessage::TurnLeft => "103 TURN LEFT".to_owned(),
-                ServerMessage::TurnRight => "104 TURN RIGHT".to_owned(),
-                ServerMessage::PickUp => "105 GET MESSAGE".to_owned(),
-                ServerMessage::Logout => "106 LOGOUT".to_owned(),
-                ServerMessage::KeyRequest => "107 KEY REQUEST".to_owned(),
-                ServerMessage::OK => "200 OK".to_owned(),
-                ServerMessage::LoginFailed => "300 LOGIN FAILED".to_owned(),
-                ServerMessage::SyntaxError => "301 SYNTAX ERROR".to_owned(),
-                ServerMessage::LogicError => "302 LOGIC ERROR".to_owned(),
-                ServerMessage::KeyOutOfRangeError => "303 KEY OUT OF RANGE".to_owned(),
-            }
-        )
+        match self {
+            ServerMessage::Confirmation(x) => x.to_string(),
+            ServerMessage::Move => "102 MOVE".to_owned(),
+            ServerMessage::TurnLeft => "103 TURN LEFT".to_owned(),
+            ServerMessage::TurnRight => "104 TURN RIGHT".to_owned(),
+            ServerMessage::PickUp => "105 GET MESSAGE".to_owned(),
+            ServerMessage::Logout => "106 LOGOUT".to_owned(),
+            ServerMessage::KeyRequest => "107 KEY REQUEST".to_owned(),
+            ServerMessage::SessionToken(token) => format!("108 SESSION {token}"),
+            ServerMessage::OK => "200 OK".to_owned(),
+            ServerMessage::LoginFailed => "300 LOGIN FAILED".to_owned(),
+            ServerMessage::SyntaxError => "301 SYNTAX ERROR".to_owned(),
+            ServerMessage::LogicError => "302 LOGIC ERROR".to_owned(),
+            ServerMessage::KeyOutOfRangeError => "303 KEY OUT OF RANGE".to_owned(),
+        }
     }
 }
 